@@ -0,0 +1,77 @@
+use std::fmt;
+
+/// The single error type used throughout Jilu.
+#[derive(Debug)]
+pub enum Error {
+    /// A Git object contained data that wasn't valid UTF-8.
+    Utf8Error,
+    /// A tag did not point at a commit, or otherwise didn't look like a tag
+    /// we know how to handle.
+    InvalidTag,
+    /// A tag name didn't match the configured `tag_pattern`.
+    TagPatternMismatch,
+    /// `create_tag` was asked to write a tag while `tag_pattern` is
+    /// configured; a read-only extraction regex can't be used to format a
+    /// new tag name.
+    UnsupportedTagPattern,
+    /// Wraps an error coming from the underlying Git library.
+    Git2(git2::Error),
+    /// Wraps a version-parsing error.
+    SemVer(semver::SemVerError),
+    /// Wraps an I/O error, e.g. while reading `.jilu.toml`.
+    Io(std::io::Error),
+    /// Wraps a TOML deserialization error, e.g. a malformed `.jilu.toml`.
+    Toml(toml::de::Error),
+    /// Wraps a regex compilation error, e.g. an invalid `tag_pattern`.
+    Regex(regex::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Utf8Error => write!(f, "encountered data that isn't valid UTF-8"),
+            Error::InvalidTag => write!(f, "tag does not point to a commit"),
+            Error::TagPatternMismatch => write!(f, "tag name does not match the configured tag pattern"),
+            Error::UnsupportedTagPattern => {
+                write!(f, "cannot create a tag while a `tag_pattern` is configured; use `tag_prefix` instead")
+            }
+            Error::Git2(err) => write!(f, "{}", err),
+            Error::SemVer(err) => write!(f, "{}", err),
+            Error::Io(err) => write!(f, "{}", err),
+            Error::Toml(err) => write!(f, "{}", err),
+            Error::Regex(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<git2::Error> for Error {
+    fn from(err: git2::Error) -> Self {
+        Error::Git2(err)
+    }
+}
+
+impl From<semver::SemVerError> for Error {
+    fn from(err: semver::SemVerError) -> Self {
+        Error::SemVer(err)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(err: toml::de::Error) -> Self {
+        Error::Toml(err)
+    }
+}
+
+impl From<regex::Error> for Error {
+    fn from(err: regex::Error) -> Self {
+        Error::Regex(err)
+    }
+}
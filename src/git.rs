@@ -4,8 +4,62 @@ use chrono::{
     DateTime,
 };
 use git2::{ObjectType, Repository, Sort};
+use once_cell::sync::Lazy;
+use rayon::prelude::*;
+use regex::Regex;
 use semver::Version;
+use serde::Deserialize;
+use std::collections::HashMap;
 use std::convert::{TryFrom, TryInto};
+use std::path::Path;
+
+/// Matches the header line of a Conventional Commits message, e.g.
+/// `feat(parser)!: add support for foo`.
+static HEADER_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^(?P<type>[a-zA-Z]+)(?P<scope>\([^)]*\))?(?P<bang>!)?: (?P<desc>.+)$").unwrap()
+});
+
+/// Matches a single footer line, e.g. `Closes #123`, `Reviewed-by: Jane`, or
+/// `Refs JIRA-456`. The last form (a bare space before the value) is
+/// ambiguous with an ordinary two-word sentence in the commit body (e.g.
+/// `Fix typo`), so `match_footer_line` only honors it for recognized
+/// reference keywords.
+static FOOTER_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"^(?P<key>[A-Za-z-]+|BREAKING CHANGE)(?:: (?P<value>.+)|\s#(?P<hash_value>\S+)|\s(?P<token_value>[A-Za-z0-9][\w./-]*))$",
+    )
+    .unwrap()
+});
+
+/// Match `line` as a footer, given `config`'s `reference_keywords`.
+///
+/// The separator-less form (`Refs JIRA-456`) is only accepted when `key` is
+/// a configured reference keyword; otherwise it's indistinguishable from
+/// ordinary prose, so the line is left alone as body text.
+fn match_footer_line<'a>(config: &Config, line: &'a str) -> Option<regex::Captures<'a>> {
+    let caps = FOOTER_RE.captures(line)?;
+    if caps.name("token_value").is_some() {
+        let key = caps.name("key").unwrap().as_str();
+        if !config.reference_keywords.contains_key(key) {
+            return None;
+        }
+    }
+    Some(caps)
+}
+
+/// Pull the captured value out of a `match_footer_line` match, regardless of
+/// which separator form (`: `, ` #`, or a bare space) it used.
+fn footer_value<'a>(caps: &regex::Captures<'a>) -> &'a str {
+    caps.name("value")
+        .or_else(|| caps.name("hash_value"))
+        .or_else(|| caps.name("token_value"))
+        .unwrap()
+        .as_str()
+}
+
+/// Matches the `Name <email>` form used in `Co-authored-by` footers.
+static CO_AUTHOR_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^(?P<name>.+) <(?P<email>[^>]+)>$").unwrap());
 
 /// A commit owning all the relevant data to be used in Jilu.
 #[derive(Debug)]
@@ -13,11 +67,27 @@ pub struct Commit {
     pub(crate) id: String,
     pub(crate) short_id: String,
     pub(crate) message: String,
+    pub(crate) commit_type: String,
+    pub(crate) scope: Option<String>,
+    pub(crate) description: String,
+    pub(crate) body: Option<String>,
+    pub(crate) breaking: bool,
+    pub(crate) footers: Vec<(String, String)>,
+    pub(crate) references: Vec<Reference>,
+    pub(crate) co_authors: Vec<Signature>,
     pub(crate) time: DateTime<Utc>,
     pub(crate) author: Signature,
     pub(crate) committer: Signature,
 }
 
+/// A reference to an external issue or ticket, extracted from a commit
+/// footer such as `Closes #123` or `Refs JIRA-456`.
+#[derive(Debug)]
+pub struct Reference {
+    pub(crate) kind: String,
+    pub(crate) id: String,
+}
+
 /// A tag owning all the relevant data to be used in Jilu.
 #[derive(Debug)]
 pub struct Tag {
@@ -37,6 +107,128 @@ pub struct Signature {
     pub(crate) time: DateTime<Utc>,
 }
 
+/// Configuration controlling how commits and tags are interpreted, loaded
+/// from a `.jilu.toml` file in the repository, mirroring the configuration
+/// file conventions used by clog and git-journal.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Prefix stripped from (and prepended to) tag names when parsing them as
+    /// versions, e.g. `v` for tags like `v1.2.0`. Ignored when `tag_pattern`
+    /// is set.
+    pub tag_prefix: String,
+    /// Regex with a capture group named `version`, used to extract the
+    /// version from a tag name instead of `tag_prefix`. Useful for monorepo
+    /// tag layouts such as `frontend-v1.2.0` or `mylib/0.3.1`.
+    pub tag_pattern: Option<String>,
+    /// Commit types (e.g. `chore`, `ci`) excluded from `commits()`.
+    pub excluded_commit_types: Vec<String>,
+    /// Maps a footer keyword (e.g. `Closes`, `Fixes`, `Refs`) to the kind of
+    /// `Reference` it produces (e.g. `closes`, `references`), so users can
+    /// add their own issue-tracker conventions.
+    pub reference_keywords: HashMap<String, String>,
+    /// Lazily-compiled, cached form of `tag_pattern`, so it's only compiled
+    /// once no matter how many tags are parsed with this `Config`.
+    #[serde(skip)]
+    tag_pattern_regex: once_cell::sync::OnceCell<Regex>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            tag_prefix: "v".to_owned(),
+            tag_pattern: None,
+            excluded_commit_types: Vec::new(),
+            reference_keywords: vec![
+                ("Closes".to_owned(), "closes".to_owned()),
+                ("Fixes".to_owned(), "closes".to_owned()),
+                ("Refs".to_owned(), "references".to_owned()),
+            ]
+            .into_iter()
+            .collect(),
+            tag_pattern_regex: once_cell::sync::OnceCell::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Load configuration from a `.jilu.toml` file at the given path.
+    ///
+    /// If the file does not exist, the default configuration is returned.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => Ok(toml::from_str(&contents)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Extract the semantic version from a tag name, using `tag_pattern` if
+    /// configured, or stripping `tag_prefix` otherwise.
+    fn extract_version(&self, name: &str) -> Result<Version, Error> {
+        match &self.tag_pattern {
+            Some(pattern) => {
+                let regex = self.tag_pattern_regex.get_or_try_init(|| Regex::new(pattern))?;
+                let version = regex
+                    .captures(name)
+                    .and_then(|caps| caps.name("version"))
+                    .ok_or(Error::TagPatternMismatch)?
+                    .as_str()
+                    .to_owned();
+                Ok(Version::parse(&version)?)
+            }
+            None => {
+                let stripped = name.strip_prefix(self.tag_prefix.as_str()).unwrap_or(name);
+                Ok(Version::parse(stripped)?)
+            }
+        }
+    }
+
+    /// Pick out the issue/ticket references among a commit's footers,
+    /// according to `reference_keywords`.
+    fn extract_references(&self, footers: &[(String, String)]) -> Vec<Reference> {
+        footers
+            .iter()
+            .filter_map(|(key, value)| {
+                self.reference_keywords.get(key).map(|kind| Reference {
+                    kind: kind.clone(),
+                    id: value.trim_start_matches('#').to_owned(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Pick out the `Co-authored-by` trailers among a commit's footers.
+///
+/// Co-authors don't carry their own timestamp, so they're given the same
+/// `time` as the commit they're attached to.
+fn extract_co_authors(footers: &[(String, String)], time: DateTime<Utc>) -> Vec<Signature> {
+    footers
+        .iter()
+        .filter(|(key, _)| key.eq_ignore_ascii_case("co-authored-by"))
+        .filter_map(|(_, value)| {
+            CO_AUTHOR_RE.captures(value).map(|caps| Signature {
+                name: caps.name("name").unwrap().as_str().to_owned(),
+                email: caps.name("email").unwrap().as_str().to_owned(),
+                time,
+            })
+        })
+        .collect()
+}
+
+/// Controls which commits `commits()` walks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalkMode {
+    /// Only follow the first parent of each commit, hiding changes that
+    /// landed through merged feature branches. Fast, and the right default
+    /// for a linear release history.
+    FirstParent,
+    /// Walk every reachable commit, including those only reachable through
+    /// merges.
+    FullHistory,
+}
+
 /// Fetch all Git commits to be presented in the change log.
 ///
 /// This function walks over a tree in the Git repository, and converts all Git
@@ -48,41 +240,74 @@ pub struct Signature {
 /// where not all commits adhere to the expected format.
 ///
 /// Any unexpected error is still bubbled up to the callee.
-pub fn commits(repo: &Repository) -> Result<Vec<Commit>, Error> {
+pub fn commits(repo: &Repository, config: &Config, mode: WalkMode) -> Result<Vec<Commit>, Error> {
     let mut walk = repo.revwalk()?;
     walk.push_head()?;
-    walk.simplify_first_parent()?;
+    if mode == WalkMode::FirstParent {
+        walk.simplify_first_parent()?;
+    }
     walk.set_sorting(Sort::REVERSE | Sort::TOPOLOGICAL)?;
 
-    // walk the tree of commits, keeping track of the object ID throughout the
-    // process to be able to point towards any commits causing an error.
-    walk.map(|result| {
-        result.map_err(|err| (None, err.into())).and_then(|oid| {
-            repo.find_commit(oid)
-                .map_err(Into::into)
-                .and_then(TryInto::try_into)
-                .map_err(|err| (Some(oid), err))
+    // Collect the OIDs up front, in their final order, so the (comparatively
+    // expensive) per-commit lookup and parsing below can happen in parallel.
+    let oids = walk.collect::<Result<Vec<_>, _>>()?;
+    let repo_path = repo.path().to_path_buf();
+
+    // Fetch and parse each commit in parallel, keeping track of the object ID
+    // throughout the process to be able to point towards any commits causing
+    // an error. `rayon` preserves the input ordering of `oids`, so the final
+    // collection below is unaffected by the parallel execution.
+    //
+    // `git2::Repository` isn't `Sync`, so each worker opens its own handle via
+    // `map_init` instead of reopening the repository for every single commit.
+    // The handle is opened lazily, on the first commit a worker processes, so
+    // a failure to reopen surfaces as a regular `Error` for that commit (and
+    // is retried for the worker's next commit) instead of panicking.
+    let results: Vec<Result<Commit, (Option<git2::Oid>, Error)>> = oids
+        .into_par_iter()
+        .map_init(
+            || None::<Repository>,
+            |repo_slot, oid| {
+                let repo = match repo_slot {
+                    Some(repo) => repo,
+                    None => {
+                        let repo =
+                            Repository::open(&repo_path).map_err(|err| (Some(oid), Error::from(err)))?;
+                        repo_slot.get_or_insert(repo)
+                    }
+                };
+                repo.find_commit(oid)
+                    .map_err(Into::into)
+                    .and_then(|commit| (config, commit).try_into())
+                    .map_err(|err| (Some(oid), err))
+            },
+        )
+        .collect();
+
+    let mut commits = results
+        .into_iter()
+        .filter_map(|result| match result {
+            Err((oid, err)) => match err {
+                // Any badly formatted commit is skipped.
+                Error::Utf8Error => {
+                    // TODO: debug logging
+                    eprintln!(
+                        "[debug] ignoring bad commit {}: {}",
+                        oid.as_ref().map(ToString::to_string).unwrap_or_default(),
+                        err
+                    );
+                    None
+                }
+                // All non-defined errors above are considered to be breaking and
+                // are bubbled up to the callee.
+                _ => Some(Err(err)),
+            },
+            Ok(commit) => Some(Ok(commit)),
         })
-    })
-    .filter_map(|result| match result {
-        Err((oid, err)) => match err {
-            // Any badly formatted commit is skipped.
-            Error::Utf8Error => {
-                // TODO: debug logging
-                eprintln!(
-                    "[debug] ignoring bad commit {}: {}",
-                    oid.as_ref().map(ToString::to_string).unwrap_or_default(),
-                    err
-                );
-                None
-            }
-            // All non-defined errors above are considered to be breaking and
-            // are bubbled up to the callee.
-            _ => Some(Err(err)),
-        },
-        Ok(commit) => Some(Ok(commit)),
-    })
-    .collect()
+        .collect::<Result<Vec<_>, _>>()?;
+
+    commits.retain(|commit| !config.excluded_commit_types.contains(&commit.commit_type));
+    Ok(commits)
 }
 
 /// Fetch all Git tags to be used as release tags in the change log.
@@ -96,7 +321,7 @@ pub fn commits(repo: &Repository) -> Result<Vec<Commit>, Error> {
 /// tags adhere to the expected format.
 ///
 /// Any unexpected error is still bubbled up to the callee.
-pub fn tags(repo: &Repository) -> Result<Vec<Tag>, Error> {
+pub fn tags(repo: &Repository, config: &Config) -> Result<Vec<Tag>, Error> {
     let mut tags: Vec<Tag> = repo
         .tag_names(None)?
         .into_iter()
@@ -110,12 +335,12 @@ pub fn tags(repo: &Repository) -> Result<Vec<Tag>, Error> {
                             Some(ObjectType::Tag) => object
                                 .into_tag()
                                 .map_err(|_| Error::InvalidTag)
-                                .and_then(TryInto::try_into),
+                                .and_then(|tag| (config, tag).try_into()),
                             // lightweight tag
                             Some(ObjectType::Commit) => object
                                 .into_commit()
                                 .map_err(|_| Error::InvalidTag)
-                                .and_then(|c| (name, c).try_into()),
+                                .and_then(|c| (config, name, c).try_into()),
                             _ => unreachable!(),
                         }
                     })
@@ -124,8 +349,8 @@ pub fn tags(repo: &Repository) -> Result<Vec<Tag>, Error> {
         })
         .filter_map(|result: Result<Tag, _>| match result {
             Err((name, err)) => match err {
-                // Any badly formatted tag is skipped.
-                Error::Utf8Error | Error::SemVer(_) => {
+                // Any badly formatted or non-matching tag is skipped.
+                Error::Utf8Error | Error::SemVer(_) | Error::TagPatternMismatch => {
                     // TODO: debug logging
                     eprintln!(
                         "[debug] ignoring bad tag {}: {}",
@@ -147,10 +372,82 @@ pub fn tags(repo: &Repository) -> Result<Vec<Tag>, Error> {
     Ok(tags)
 }
 
-impl TryFrom<git2::Commit<'_>> for Commit {
+/// Compute the next semantic version, given the existing tags and the commits
+/// that have landed since the latest one.
+///
+/// Follows the Conventional Commits bump rules: a breaking commit bumps
+/// major, otherwise a `feat` commit bumps minor, otherwise a `fix` commit
+/// bumps patch. Resetting only happens towards zero, e.g. a major bump resets
+/// minor and patch. If none of the commits qualify, the current version (or
+/// `0.0.0` if there is no tag yet) is returned unchanged.
+pub fn next_version(tags: &[Tag], commits: &[Commit]) -> Version {
+    let latest_tag = tags.iter().max_by_key(|tag| &tag.version);
+    let current = latest_tag
+        .map(|tag| tag.version.clone())
+        .unwrap_or_else(|| Version::new(0, 0, 0));
+
+    // Only commits that landed after the latest tag's commit are relevant to
+    // the bump decision. Filtering by time (rather than looking for the tag's
+    // commit by id) is deliberate: that commit may be missing from `commits`
+    // entirely, e.g. because its type is excluded, it's off the first-parent
+    // path, or it was skipped as malformed.
+    let relevant: Vec<&Commit> = match latest_tag {
+        Some(tag) => commits
+            .iter()
+            .filter(|commit| commit.time > tag.commit.time)
+            .collect(),
+        None => commits.iter().collect(),
+    };
+
+    if relevant.iter().any(|commit| commit.breaking) {
+        Version::new(current.major + 1, 0, 0)
+    } else if relevant.iter().any(|commit| commit.commit_type == "feat") {
+        Version::new(current.major, current.minor + 1, 0)
+    } else if relevant.iter().any(|commit| commit.commit_type == "fix") {
+        Version::new(current.major, current.minor, current.patch + 1)
+    } else {
+        current
+    }
+}
+
+/// Create an annotated tag on `HEAD` for the given version, honoring the
+/// configured tag prefix, consistent with the prefix-stripping logic used
+/// when reading tags back in.
+pub fn create_tag(repo: &Repository, config: &Config, version: &Version) -> Result<(), Error> {
+    // `tag_pattern` is a read-only extraction rule (an arbitrary regex with a
+    // capture group), not a template we can format a new tag name from, so a
+    // configuration using it can't be reconciled with tag creation here.
+    if config.tag_pattern.is_some() {
+        return Err(Error::UnsupportedTagPattern);
+    }
+
+    let head = repo.head()?.peel_to_commit()?;
+    let signature = repo.signature()?;
+    let name = format!("{}{}", config.tag_prefix, version);
+
+    repo.tag(
+        &name,
+        head.as_object(),
+        &signature,
+        &format!("Release {}", name),
+        false,
+    )?;
+
+    Ok(())
+}
+
+impl TryFrom<(&Config, git2::Commit<'_>)> for Commit {
     type Error = Error;
 
-    fn try_from(commit: git2::Commit<'_>) -> Result<Self, Error> {
+    fn try_from((config, commit): (&Config, git2::Commit<'_>)) -> Result<Self, Error> {
+        let message = commit
+            .message()
+            .ok_or(Error::Utf8Error)?
+            .trim_end()
+            .to_owned();
+        let parsed = ParsedMessage::parse(config, &message);
+        let time = Utc.timestamp(commit.time().seconds(), 0);
+
         Ok(Self {
             id: commit.id().to_string(),
             short_id: commit
@@ -159,32 +456,116 @@ impl TryFrom<git2::Commit<'_>> for Commit {
                 .as_str()
                 .ok_or(Error::Utf8Error)?
                 .to_owned(),
-            message: commit
-                .message()
-                .ok_or(Error::Utf8Error)?
-                .trim_end()
-                .to_owned(),
+            commit_type: parsed.commit_type,
+            scope: parsed.scope,
+            description: parsed.description,
+            body: parsed.body,
+            breaking: parsed.breaking,
+            references: config.extract_references(&parsed.footers),
+            co_authors: extract_co_authors(&parsed.footers, time),
+            footers: parsed.footers,
+            message,
             author: commit.author().try_into()?,
             committer: commit.committer().try_into()?,
-            time: Utc.timestamp(commit.time().seconds(), 0),
+            time,
         })
     }
 }
 
-impl TryFrom<git2::Tag<'_>> for Tag {
+/// The pieces of a commit message as laid out by the Conventional Commits
+/// specification.
+///
+/// Messages that don't match the expected header format are not treated as an
+/// error; they're kept around with an empty `commit_type` and the full summary
+/// line as their `description`, in keeping with jilu's "skip/ignore malformed"
+/// philosophy.
+struct ParsedMessage {
+    commit_type: String,
+    scope: Option<String>,
+    description: String,
+    body: Option<String>,
+    breaking: bool,
+    footers: Vec<(String, String)>,
+}
+
+impl ParsedMessage {
+    fn parse(config: &Config, message: &str) -> Self {
+        let mut lines = message.lines();
+        let summary = lines.next().unwrap_or_default();
+
+        let (commit_type, scope, bang, description) = match HEADER_RE.captures(summary) {
+            Some(caps) => (
+                caps.name("type").unwrap().as_str().to_owned(),
+                caps.name("scope")
+                    .map(|m| m.as_str().trim_matches(|c| c == '(' || c == ')').to_owned()),
+                caps.name("bang").is_some(),
+                caps.name("desc").unwrap().as_str().to_owned(),
+            ),
+            None => (String::new(), None, false, summary.to_owned()),
+        };
+
+        // Everything after the first blank line is the body, minus a
+        // trailing block of footer lines.
+        let rest: Vec<&str> = lines.collect();
+        let rest = match rest.iter().position(|line| line.is_empty()) {
+            Some(index) => &rest[index + 1..],
+            None => &[][..],
+        };
+
+        let mut footer_start = rest.len();
+        for line in rest.iter().rev() {
+            if line.is_empty() || match_footer_line(config, line).is_some() {
+                footer_start -= 1;
+            } else {
+                break;
+            }
+        }
+
+        let (body_lines, footer_lines) = rest.split_at(footer_start);
+        let body = {
+            let joined = body_lines.join("\n");
+            let trimmed = joined.trim();
+            (!trimmed.is_empty()).then(|| trimmed.to_owned())
+        };
+
+        let footers: Vec<(String, String)> = footer_lines
+            .iter()
+            .filter_map(|line| {
+                match_footer_line(config, line).map(|caps| {
+                    (
+                        caps.name("key").unwrap().as_str().to_owned(),
+                        footer_value(&caps).to_owned(),
+                    )
+                })
+            })
+            .collect();
+
+        let breaking = bang
+            || footers
+                .iter()
+                .any(|(key, _)| key == "BREAKING CHANGE" || key == "BREAKING-CHANGE");
+
+        Self {
+            commit_type,
+            scope,
+            description,
+            body,
+            breaking,
+            footers,
+        }
+    }
+}
+
+impl TryFrom<(&Config, git2::Tag<'_>)> for Tag {
     type Error = Error;
 
-    fn try_from(tag: git2::Tag<'_>) -> Result<Self, Error> {
+    fn try_from((config, tag): (&Config, git2::Tag<'_>)) -> Result<Self, Error> {
         if tag.target_type() != Some(ObjectType::Commit) {
             return Err(Error::InvalidTag);
         }
 
         let name = tag.name().ok_or(Error::Utf8Error)?.to_owned();
-        let version = Version::parse(if name.starts_with('v') {
-            &name[1..]
-        } else {
-            &name
-        })?;
+        let version = config.extract_version(&name)?;
 
         Ok(Self {
             id: tag.id().to_string(),
@@ -192,10 +573,12 @@ impl TryFrom<git2::Tag<'_>> for Tag {
             name,
             version,
             tagger: tag.tagger().map(TryInto::try_into).transpose()?,
-            commit: tag
-                .target()?
-                .into_commit()
-                .map_err(|_| git2::Error::from_str("tag does not point to commit"))?
+            commit: (
+                config,
+                tag.target()?
+                    .into_commit()
+                    .map_err(|_| git2::Error::from_str("tag does not point to commit"))?,
+            )
                 .try_into()?,
         })
     }
@@ -212,15 +595,11 @@ impl TryFrom<git2::Signature<'_>> for Signature {
         })
     }
 }
-impl TryFrom<(&str, git2::Commit<'_>)> for Tag {
+impl TryFrom<(&Config, &str, git2::Commit<'_>)> for Tag {
     type Error = Error;
 
-    fn try_from((name, commit): (&str, git2::Commit<'_>)) -> Result<Self, Error> {
-        let version = Version::parse(if name.starts_with('v') {
-            &name[1..]
-        } else {
-            &name
-        })?;
+    fn try_from((config, name, commit): (&Config, &str, git2::Commit<'_>)) -> Result<Self, Error> {
+        let version = config.extract_version(name)?;
 
         Ok(Self {
             id: commit.id().to_string(),
@@ -228,7 +607,7 @@ impl TryFrom<(&str, git2::Commit<'_>)> for Tag {
             name: name.to_owned(),
             version,
             tagger: Some(commit.author().try_into()?),
-            commit: commit.try_into()?,
+            commit: (config, commit).try_into()?,
         })
     }
 }
@@ -0,0 +1,5 @@
+mod error;
+mod git;
+
+pub use error::Error;
+pub use git::*;